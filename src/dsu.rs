@@ -0,0 +1,501 @@
+//! # dsu
+//!
+//! A `MotionServer` that speaks the Cemuhook "DSU" UDP protocol, so
+//! emulators and other third-party tools can pull button/stick/motion
+//! data from a `SixAxis` over the network instead of linking this
+//! crate directly.
+//!
+//! This implements the subset of DSU that matters for a single pad:
+//! protocol version, controller info, and pad-data subscription/push.
+//! It does not implement multi-slot enumeration beyond "slot 0 is
+//! whatever `SixAxis` we were given".
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use std::collections::HashSet;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use {Axis, Button, Error, Motion, Result, Shoulder, SixAxis};
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// Serves one `SixAxis` controller's state over the network using the
+/// Cemuhook/DSU UDP protocol.
+pub struct MotionServer {
+    server_id: u32,
+    shutdown: Arc<AtomicBool>,
+    child: Option<thread::JoinHandle<()>>,
+}
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+const MAGIC_SERVER: &[u8; 4] = b"DSUS";
+const MAGIC_CLIENT: &[u8; 4] = b"DSUC";
+const PROTOCOL_VERSION: u16 = 1001;
+
+const MSG_TYPE_VERSION: u32 = 0x100000;
+const MSG_TYPE_INFO: u32 = 0x100001;
+const MSG_TYPE_DATA: u32 = 0x100002;
+
+/// Header size common to every DSU packet: magic(4) + version(2) +
+/// length(2) + crc32(4) + id(4).
+const HEADER_LEN: usize = 16;
+
+/// How often we push a data packet to every subscribed client. This
+/// also doubles as the client socket's read timeout, so the send loop
+/// and the request loop share one thread.
+const SEND_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Roughly how many raw accelerometer counts make up 1g, per
+/// `Motion`'s doc comment.
+const ACCEL_COUNTS_PER_G: f32 = 113.0;
+
+/// An approximate raw-counts-per-degree-per-second scale for the
+/// DUALSHOCK3's yaw gyro; the kernel driver doesn't report a ground
+/// truth for this so, as `Motion`'s doc comment says, it's a rough figure.
+const GYRO_COUNTS_PER_DEG_PER_SEC: f32 = 123.0;
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+impl MotionServer {
+    /// Bind a UDP socket at `bind_addr` and start serving `controller`'s
+    /// state to any DSU client that subscribes.
+    pub fn new<A: ::std::net::ToSocketAddrs>(
+        bind_addr: A,
+        controller: Arc<SixAxis>,
+    ) -> Result<MotionServer> {
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.set_read_timeout(Some(SEND_INTERVAL))?;
+        let socket = Arc::new(socket);
+        let clients = Arc::new(Mutex::new(HashSet::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        // Any fixed value works as long as it's stable for the
+        // lifetime of the server; DSU clients use it to notice a
+        // server restart.
+        let server_id = 0x5349_5841; // "SIXA", arbitrary but recognisable
+
+        let socket_ref = socket.clone();
+        let clients_ref = clients.clone();
+        let controller_ref = controller.clone();
+        let shutdown_ref = shutdown.clone();
+        let child = thread::spawn(move || {
+            let mut packet_number: u32 = 0;
+            let mut buf = [0u8; 128];
+            loop {
+                if shutdown_ref.load(Ordering::SeqCst) {
+                    break;
+                }
+                match socket_ref.recv_from(&mut buf) {
+                    Ok((n, addr)) => {
+                        handle_request(&buf[..n], addr, &socket_ref, server_id, &clients_ref);
+                    }
+                    Err(ref e)
+                        if e.kind() == ::std::io::ErrorKind::WouldBlock
+                            || e.kind() == ::std::io::ErrorKind::TimedOut => {}
+                    Err(_) => break,
+                }
+                send_data_to_clients(
+                    &socket_ref,
+                    server_id,
+                    &mut packet_number,
+                    &clients_ref,
+                    &controller_ref,
+                );
+            }
+        });
+        Ok(MotionServer {
+            server_id,
+            shutdown,
+            child: Some(child),
+        })
+    }
+
+    /// Stop serving and wait for the background thread to exit.
+    pub fn close(&mut self) -> Result<()> {
+        match self.child.take() {
+            None => Err(Error::NotOpen),
+            Some(handle) => {
+                self.shutdown.store(true, Ordering::SeqCst);
+                handle.join().map_err(|_| Error::UnknownError)
+            }
+        }
+    }
+}
+
+impl Drop for MotionServer {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+impl ::std::fmt::Debug for MotionServer {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "<MotionServer server_id={:#x}>", self.server_id)
+    }
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+fn handle_request(
+    buf: &[u8],
+    addr: SocketAddr,
+    socket: &UdpSocket,
+    server_id: u32,
+    clients: &Arc<Mutex<HashSet<SocketAddr>>>,
+) {
+    if buf.len() < HEADER_LEN + 4 || &buf[0..4] != MAGIC_CLIENT {
+        return;
+    }
+    let msg_type = LittleEndian::read_u32(&buf[HEADER_LEN..HEADER_LEN + 4]);
+    let payload = &buf[HEADER_LEN + 4..];
+    match msg_type {
+        MSG_TYPE_VERSION => {
+            let mut reply_payload = [0u8; 4];
+            LittleEndian::write_u16(&mut reply_payload[0..2], PROTOCOL_VERSION);
+            let packet = make_packet(server_id, MSG_TYPE_VERSION, &reply_payload);
+            let _ = socket.send_to(&packet, addr);
+        }
+        MSG_TYPE_INFO => {
+            // One pad, slot 0, always connected.
+            let mut reply_payload = [0u8; 12];
+            reply_payload[0] = 0; // slot
+            reply_payload[1] = 2; // slot state: connected
+            reply_payload[2] = 1; // device model: DS3 (partial gyro)
+            reply_payload[3] = 2; // connection type: USB/Bluetooth, unspecified
+            // reply_payload[4..10] left as a zero MAC address
+            reply_payload[10] = 0xef; // battery: N/A
+            reply_payload[11] = 0;
+            let packet = make_packet(server_id, MSG_TYPE_INFO, &reply_payload);
+            let _ = socket.send_to(&packet, addr);
+        }
+        // Subscription request: remember this client, ignoring which
+        // slot/flags it asked for since we only ever have slot 0.
+        MSG_TYPE_DATA if !payload.is_empty() => {
+            clients.lock().unwrap().insert(addr);
+        }
+        _ => {}
+    }
+}
+
+fn send_data_to_clients(
+    socket: &UdpSocket,
+    server_id: u32,
+    packet_number: &mut u32,
+    clients: &Arc<Mutex<HashSet<SocketAddr>>>,
+    controller: &Arc<SixAxis>,
+) {
+    let addrs: Vec<SocketAddr> = {
+        let clients = clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        clients.iter().cloned().collect()
+    };
+
+    *packet_number = packet_number.wrapping_add(1);
+    let payload = build_data_payload(*packet_number, controller);
+    let packet = make_packet(server_id, MSG_TYPE_DATA, &payload);
+
+    let mut clients = clients.lock().unwrap();
+    for addr in addrs {
+        if socket.send_to(&packet, addr).is_err() {
+            // Client's gone (e.g. connection refused on a closed port);
+            // stop pushing to it.
+            clients.remove(&addr);
+        }
+    }
+}
+
+/// Build a `PadDataResponse` payload (everything after the `EventType`
+/// field), matching the published Cemuhook/DSU wire format field-for-
+/// field so real clients (Dolphin, Cemu, DS4Windows, ...) don't desync
+/// reading it at fixed byte offsets.
+fn build_data_payload(packet_number: u32, controller: &Arc<SixAxis>) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(80);
+    payload.push(0); // slot
+    payload.push(2); // slot state: connected
+    payload.push(1); // device model: DS3 (partial gyro)
+    payload.push(2); // connection type
+    payload.extend_from_slice(&[0u8; 6]); // MAC address, unknown
+    payload.push(0xef); // battery: N/A
+    payload.push(1); // is_connected
+
+    let mut packet_number_buf = [0u8; 4];
+    LittleEndian::write_u32(&mut packet_number_buf, packet_number);
+    payload.extend_from_slice(&packet_number_buf);
+
+    payload.push(buttons1(controller));
+    payload.push(buttons2(controller));
+    payload.push(button_byte(controller, Button::PS));
+    payload.push(0); // touch button: the SIXAXIS/DUALSHOCK3 has no touchpad
+
+    for axis in [Axis::LX, Axis::LY, Axis::RX, Axis::RY].iter().cloned() {
+        let raw = controller.read_axis(axis).unwrap_or(0);
+        payload.push(axis_to_dsu_byte(raw));
+    }
+
+    // D-pad and face button analog press intensity. The SIXAXIS/
+    // DUALSHOCK3 only reports these digitally, so each is either fully
+    // off or fully on; L1/R1/L2/R2 do have real analog readings.
+    payload.push(button_byte(controller, Button::Left));
+    payload.push(button_byte(controller, Button::Down));
+    payload.push(button_byte(controller, Button::Right));
+    payload.push(button_byte(controller, Button::Up));
+    payload.push(button_byte(controller, Button::Triangle));
+    payload.push(button_byte(controller, Button::Circle));
+    payload.push(button_byte(controller, Button::Cross));
+    payload.push(button_byte(controller, Button::Square));
+    payload.push(shoulder_to_dsu_byte(controller, Shoulder::R1));
+    payload.push(shoulder_to_dsu_byte(controller, Shoulder::L1));
+    payload.push(shoulder_to_dsu_byte(controller, Shoulder::R2));
+    payload.push(shoulder_to_dsu_byte(controller, Shoulder::L2));
+
+    // No touchpad, so both touch records are reported inactive.
+    payload.extend_from_slice(&[0u8; 6]); // first touch: active, id, x, y
+    payload.extend_from_slice(&[0u8; 6]); // second touch: active, id, x, y
+
+    let micros = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0);
+    let mut timestamp_buf = [0u8; 8];
+    LittleEndian::write_u64(&mut timestamp_buf, micros);
+    payload.extend_from_slice(&timestamp_buf);
+
+    let (ax, ay, az) = read_accel_g(controller);
+    // `SixAxis` only models yaw rotation, not the full pitch/yaw/roll
+    // triplet DSU's wire format has room for, so pitch and roll are
+    // reported as zero.
+    let gyro_yaw = read_gyro_deg_per_sec(controller);
+    for value in [ax, ay, az, 0.0, gyro_yaw, 0.0].iter() {
+        let mut buf = [0u8; 4];
+        LittleEndian::write_f32(&mut buf, *value);
+        payload.extend_from_slice(&buf);
+    }
+
+    payload
+}
+
+/// `Share`/`L3`/`R3`/`Start`/`Up`/`Right`/`Down`/`Left`, one bit each,
+/// LSB first - DSU's first button bitflag byte.
+fn buttons1(controller: &Arc<SixAxis>) -> u8 {
+    let buttons = [
+        Button::Select,
+        Button::LeftStick,
+        Button::RightStick,
+        Button::Start,
+        Button::Up,
+        Button::Right,
+        Button::Down,
+        Button::Left,
+    ];
+    bitmask(controller, &buttons)
+}
+
+/// `L2`/`R2`/`L1`/`R1`/`Triangle`/`Circle`/`Cross`/`Square`, one bit
+/// each, LSB first - DSU's second button bitflag byte.
+fn buttons2(controller: &Arc<SixAxis>) -> u8 {
+    let buttons = [
+        Button::L2,
+        Button::R2,
+        Button::L1,
+        Button::R1,
+        Button::Triangle,
+        Button::Circle,
+        Button::Cross,
+        Button::Square,
+    ];
+    bitmask(controller, &buttons)
+}
+
+fn bitmask(controller: &Arc<SixAxis>, buttons: &[Button]) -> u8 {
+    let mut mask: u8 = 0;
+    for (i, button) in buttons.iter().cloned().enumerate() {
+        if controller.read_button(button).unwrap_or(false) {
+            mask |= 1 << i;
+        }
+    }
+    mask
+}
+
+/// `0xff` if `button` is pressed, `0x00` otherwise - DSU's analog
+/// press-intensity encoding for buttons we only report digitally.
+fn button_byte(controller: &Arc<SixAxis>, button: Button) -> u8 {
+    if controller.read_button(button).unwrap_or(false) {
+        0xff
+    } else {
+        0x00
+    }
+}
+
+/// Rescale a `read_axis` value (-32768..32767) down to the 0..255 byte
+/// DSU uses on the wire.
+fn axis_to_dsu_byte(value: i16) -> u8 {
+    (((value as i32) + 32768) / 256) as u8
+}
+
+/// Rescale a `read_shoulder` value (0..65535) down to the 0..255 byte
+/// DSU uses for analog button press intensity.
+fn shoulder_to_dsu_byte(controller: &Arc<SixAxis>, shoulder: Shoulder) -> u8 {
+    (controller.read_shoulder(shoulder).unwrap_or(0) >> 8) as u8
+}
+
+fn read_accel_g(controller: &Arc<SixAxis>) -> (f32, f32, f32) {
+    let x = controller.read_motion(Motion::AccelX).unwrap_or(0) as f32 / ACCEL_COUNTS_PER_G;
+    let y = controller.read_motion(Motion::AccelY).unwrap_or(0) as f32 / ACCEL_COUNTS_PER_G;
+    let z = controller.read_motion(Motion::AccelZ).unwrap_or(0) as f32 / ACCEL_COUNTS_PER_G;
+    (x, y, z)
+}
+
+/// `SixAxis` only models yaw rotation, not the full pitch/yaw/roll
+/// triplet DSU's wire format has room for, so pitch and roll are
+/// reported as zero.
+fn read_gyro_deg_per_sec(controller: &Arc<SixAxis>) -> f32 {
+    controller.read_motion(Motion::GyroYaw).unwrap_or(0) as f32 / GYRO_COUNTS_PER_DEG_PER_SEC
+}
+
+/// Assemble a full DSU packet: header (with a correct CRC32) followed
+/// by `msg_type` and `msg_payload`.
+fn make_packet(server_id: u32, msg_type: u32, msg_payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + 4 + msg_payload.len());
+    packet.extend_from_slice(MAGIC_SERVER);
+    let mut version_buf = [0u8; 2];
+    LittleEndian::write_u16(&mut version_buf, PROTOCOL_VERSION);
+    packet.extend_from_slice(&version_buf);
+
+    let length = (4 + msg_payload.len()) as u16;
+    let mut length_buf = [0u8; 2];
+    LittleEndian::write_u16(&mut length_buf, length);
+    packet.extend_from_slice(&length_buf);
+
+    // CRC32 field: zeroed for now, patched in below once the whole
+    // packet (crc field included) has been assembled.
+    packet.extend_from_slice(&[0u8; 4]);
+
+    let mut id_buf = [0u8; 4];
+    LittleEndian::write_u32(&mut id_buf, server_id);
+    packet.extend_from_slice(&id_buf);
+
+    let mut type_buf = [0u8; 4];
+    LittleEndian::write_u32(&mut type_buf, msg_type);
+    packet.extend_from_slice(&type_buf);
+
+    packet.extend_from_slice(msg_payload);
+
+    let crc = crc32(&packet);
+    let mut crc_buf = [0u8; 4];
+    LittleEndian::write_u32(&mut crc_buf, crc);
+    packet[8..12].copy_from_slice(&crc_buf);
+
+    packet
+}
+
+/// Standard CRC-32 (IEEE 802.3 polynomial), computed bit-by-bit. DSU's
+/// framing calls for this with the packet's own CRC field zeroed.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xedb8_8320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+// ****************************************************************************
+//
+// Tests
+//
+// ****************************************************************************
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A never-opened `SixAxis` reports every axis/button/motion as its
+    /// rest value, which pins down the fixed byte offsets of a
+    /// `PadDataResponse` payload for a controller nobody's touching.
+    #[test]
+    fn data_payload_matches_dsu_layout_at_rest() {
+        let controller = Arc::new(SixAxis::new("/dev/null"));
+        let payload = build_data_payload(1, &controller);
+
+        assert_eq!(payload.len(), 80);
+
+        assert_eq!(payload[0], 0); // slot
+        assert_eq!(payload[1], 2); // slot state: connected
+        assert_eq!(payload[2], 1); // device model: DS3
+        assert_eq!(payload[3], 2); // connection type
+        assert_eq!(&payload[4..10], &[0u8; 6]); // MAC
+        assert_eq!(payload[11], 1); // is_connected
+
+        assert_eq!(LittleEndian::read_u32(&payload[12..16]), 1); // packet number
+
+        assert_eq!(payload[16], 0); // buttons1: nothing pressed
+        assert_eq!(payload[17], 0); // buttons2: nothing pressed
+        assert_eq!(payload[18], 0); // PS button
+        assert_eq!(payload[19], 0); // touch button: no touchpad
+
+        // Sticks centred: axis_to_dsu_byte(0) == 0x80.
+        assert_eq!(&payload[20..24], &[0x80, 0x80, 0x80, 0x80]);
+
+        // D-pad/face/shoulder analog press intensity: all released.
+        assert_eq!(&payload[24..36], &[0u8; 12]);
+
+        // No touchpad: both touch records inactive.
+        assert_eq!(&payload[36..48], &[0u8; 12]);
+
+        // Motion: at rest, every axis reads 0 raw counts.
+        for chunk in payload[56..80].chunks(4) {
+            assert_eq!(LittleEndian::read_u32(chunk), 0.0f32.to_bits());
+        }
+    }
+
+    #[test]
+    fn buttons1_and_buttons2_zero_when_nothing_pressed() {
+        let controller = Arc::new(SixAxis::new("/dev/null"));
+        // There's no device to actually press a button on, but the
+        // bitmask helper only reads `State`, so an empty one is enough
+        // to pin the "nothing pressed" baseline other tests build on.
+        assert_eq!(buttons1(&controller), 0);
+        assert_eq!(buttons2(&controller), 0);
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
@@ -0,0 +1,430 @@
+//! # evdev
+//!
+//! Low-level support for talking to the Linux `evdev` input layer
+//! (`/dev/input/eventN`), as opposed to the older `joydev` layer
+//! (`/dev/input/jsN`) that this crate used to read.
+//!
+//! This module only knows about raw kernel types: `struct input_event`,
+//! the `EV_*`/`ABS_*`/`KEY_*` constants it cares about, and the ioctls
+//! used to query device capabilities. It has no idea what a `SixAxis`
+//! is; `lib.rs` maps these raw codes onto `Axis`/`Shoulder`/`Button`.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+use byteorder::{ByteOrder, NativeEndian};
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// Size in bytes of a `struct input_event` on a 64-bit Linux host:
+/// `struct timeval` (16 bytes) + `type` (2) + `code` (2) + `value` (4).
+pub const INPUT_EVENT_SIZE: usize = 24;
+
+/// One decoded `struct input_event`.
+#[derive(Debug, Copy, Clone)]
+pub struct RawEvent {
+    /// Event class, e.g. `EV_ABS`, `EV_KEY`, `EV_SYN`.
+    pub ev_type: u16,
+    /// Which control within the class, e.g. `ABS_X`, `BTN_SOUTH`.
+    pub code: u16,
+    /// The new value.
+    pub value: i32,
+}
+
+/// The kernel's report of one absolute axis's range, via `EVIOCGABS`.
+///
+/// This mirrors `struct input_absinfo` field-for-field (not just the
+/// parts we currently use) since the ioctl writes into it in place;
+/// dropping a field would shift everything after it out of line.
+#[derive(Debug, Copy, Clone)]
+#[allow(dead_code)]
+pub struct AbsInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+/// The USB/Bluetooth identity of a device, via `EVIOCGID`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+// ****************************************************************************
+//
+// Public Data
+//
+// ****************************************************************************
+
+// EV_* event classes
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_FF: u16 = 0x15;
+pub const EV_LED: u16 = 0x11;
+
+// SYN_* codes
+pub const SYN_DROPPED: u16 = 3;
+
+// ABS_* axis codes we care about (thumb sticks + motion)
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_RX: u16 = 0x03;
+pub const ABS_RY: u16 = 0x04;
+pub const ABS_THROTTLE: u16 = 0x06;
+pub const ABS_RUDDER: u16 = 0x07;
+pub const ABS_WHEEL: u16 = 0x08;
+pub const ABS_GAS: u16 = 0x09;
+pub const ABS_TILT_X: u16 = 0x1a;
+pub const ABS_TILT_Y: u16 = 0x1b;
+pub const ABS_TOOL_WIDTH: u16 = 0x1c;
+pub const ABS_MISC: u16 = 0x28;
+
+// BTN_* button codes the DUALSHOCK3 reports
+pub const BTN_SELECT: u16 = 0x13a;
+pub const BTN_THUMBL: u16 = 0x13d;
+pub const BTN_THUMBR: u16 = 0x13e;
+pub const BTN_START: u16 = 0x13b;
+pub const BTN_DPAD_UP: u16 = 0x220;
+pub const BTN_DPAD_RIGHT: u16 = 0x223;
+pub const BTN_DPAD_DOWN: u16 = 0x221;
+pub const BTN_DPAD_LEFT: u16 = 0x222;
+pub const BTN_TL2: u16 = 0x138;
+pub const BTN_TR2: u16 = 0x139;
+pub const BTN_TL: u16 = 0x136;
+pub const BTN_TR: u16 = 0x137;
+pub const BTN_MODE: u16 = 0x13c;
+pub const BTN_NORTH: u16 = 0x133;
+pub const BTN_EAST: u16 = 0x131;
+pub const BTN_SOUTH: u16 = 0x130;
+pub const BTN_WEST: u16 = 0x134;
+
+const KEY_MAX: usize = 0x2ff;
+const ABS_MAX: usize = 0x3f;
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+// We only use ioctl numbers for the `evdev` calls this crate needs, rather
+// than pulling in the whole `EVIOCG*` macro expansion. These are computed
+// the same way `<linux/input.h>` computes them (`_IOR`/`_IOC`).
+pub(crate) const IOC_WRITE: u32 = 1;
+const IOC_READ: u32 = 2;
+const IOC_NRBITS: u32 = 8;
+const IOC_TYPEBITS: u32 = 8;
+const IOC_SIZEBITS: u32 = 14;
+const IOC_NRSHIFT: u32 = 0;
+const IOC_TYPESHIFT: u32 = IOC_NRSHIFT + IOC_NRBITS;
+const IOC_SIZESHIFT: u32 = IOC_TYPESHIFT + IOC_TYPEBITS;
+const IOC_DIRSHIFT: u32 = IOC_SIZESHIFT + IOC_SIZEBITS;
+
+pub(crate) const fn ioc(dir: u32, ty: u32, nr: u32, size: u32) -> u64 {
+    ((dir << IOC_DIRSHIFT)
+        | (ty << IOC_TYPESHIFT)
+        | (nr << IOC_NRSHIFT)
+        | (size << IOC_SIZESHIFT)) as u64
+}
+
+pub(crate) const EV_IOC_MAGIC: u32 = b'E' as u32;
+
+/// `EVIOCGID` - get device input id
+fn eviocgid() -> u64 {
+    ioc(IOC_READ, EV_IOC_MAGIC, 0x02, mem::size_of::<InputId>() as u32)
+}
+
+/// `EVIOCGABS(abs)` - get absolute axis info
+fn eviocgabs(abs: u16) -> u64 {
+    ioc(
+        IOC_READ,
+        EV_IOC_MAGIC,
+        0x40 + abs as u32,
+        mem::size_of::<AbsInfo>() as u32,
+    )
+}
+
+/// `EVIOCGBIT(ev, len)` - get event bits
+fn eviocgbit(ev: u16, len: u32) -> u64 {
+    ioc(IOC_READ, EV_IOC_MAGIC, 0x20 + ev as u32, len)
+}
+
+/// `EVIOCGKEY(len)` - get global key state
+fn eviocgkey(len: u32) -> u64 {
+    ioc(IOC_READ, EV_IOC_MAGIC, 0x18, len)
+}
+
+/// `EVIOCGNAME(len)` - get device name string
+fn eviocgname(len: u32) -> u64 {
+    ioc(IOC_READ, EV_IOC_MAGIC, 0x06, len)
+}
+
+/// Devices don't report a name longer than this; long enough for
+/// anything the SIXAXIS/DUALSHOCK3 driver sends.
+const NAME_BUF_LEN: usize = 256;
+
+const F_GETFL: i32 = 3;
+const F_SETFL: i32 = 4;
+const O_NONBLOCK: i32 = 0o4000;
+const POLLIN: i16 = 0x0001;
+
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+extern "C" {
+    fn ioctl(fd: i32, request: u64, ...) -> i32;
+    fn fcntl(fd: i32, cmd: i32, arg: i32) -> i32;
+    fn pipe(fds: *mut i32) -> i32;
+    fn poll(fds: *mut PollFd, nfds: u64, timeout: i32) -> i32;
+    fn close(fd: i32) -> i32;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+}
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Parse one `struct input_event` out of a raw 24-byte buffer.
+pub fn parse_event(buf: &[u8; INPUT_EVENT_SIZE]) -> RawEvent {
+    // buf[0..16] is `struct timeval`; we don't need the timestamp.
+    let ev_type = NativeEndian::read_u16(&buf[16..18]);
+    let code = NativeEndian::read_u16(&buf[18..20]);
+    let value = NativeEndian::read_i32(&buf[20..24]);
+    RawEvent {
+        ev_type,
+        code,
+        value,
+    }
+}
+
+/// Query the device's declared input id (vendor/product/bustype/version).
+pub fn get_input_id(f: &File) -> io::Result<InputId> {
+    let mut id: InputId = unsafe { mem::zeroed() };
+    ioctl_get(f, eviocgid(), &mut id)?;
+    Ok(id)
+}
+
+/// Query the kernel's `EVIOCGABS` info for one absolute axis.
+pub fn get_abs_info(f: &File, abs_code: u16) -> io::Result<AbsInfo> {
+    let mut info: AbsInfo = unsafe { mem::zeroed() };
+    ioctl_get(f, eviocgabs(abs_code), &mut info)?;
+    Ok(info)
+}
+
+/// Query which codes of event class `ev` (e.g. `EV_KEY`, `EV_ABS`) this
+/// device supports, as a little-endian kernel bitfield.
+pub fn get_event_bits(f: &File, ev: u16) -> io::Result<Vec<u8>> {
+    let len = if ev == EV_ABS {
+        (ABS_MAX / 8) + 1
+    } else {
+        (KEY_MAX / 8) + 1
+    };
+    let mut bits = vec![0u8; len];
+    ioctl_get_slice(f, eviocgbit(ev, len as u32), &mut bits)?;
+    Ok(bits)
+}
+
+/// Query the current (sticky) state of every `EV_KEY` code, as a
+/// little-endian kernel bitfield. Used to resynchronize after
+/// `SYN_DROPPED`.
+pub fn get_key_state(f: &File) -> io::Result<Vec<u8>> {
+    let len = (KEY_MAX / 8) + 1;
+    let mut bits = vec![0u8; len];
+    ioctl_get_slice(f, eviocgkey(len as u32), &mut bits)?;
+    Ok(bits)
+}
+
+/// Query the device's human-readable name, e.g.
+/// `"Sony PLAYSTATION(R)3 Controller"`.
+pub fn get_name(f: &File) -> io::Result<String> {
+    let mut buf = [0u8; NAME_BUF_LEN];
+    ioctl_get_slice(f, eviocgname(NAME_BUF_LEN as u32), &mut buf)?;
+    let nul = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..nul]).into_owned())
+}
+
+/// Run an ioctl that reads, writes, or both reads and writes `val` in
+/// place - used by `ff.rs` for `EVIOCSFF`, where the kernel both
+/// consumes the uploaded effect and writes the assigned effect id back
+/// into it.
+pub(crate) fn ioctl_rw<T>(f: &File, request: u64, val: &mut T) -> io::Result<()> {
+    ioctl_get(f, request, val)
+}
+
+/// Write a single `struct input_event` to `f`, e.g. to play a force
+/// feedback effect (`EV_FF`) or set an LED (`EV_LED`).
+pub fn write_event(f: &mut File, ev_type: u16, code: u16, value: i32) -> io::Result<()> {
+    let mut buf = [0u8; INPUT_EVENT_SIZE];
+    NativeEndian::write_u16(&mut buf[16..18], ev_type);
+    NativeEndian::write_u16(&mut buf[18..20], code);
+    NativeEndian::write_i32(&mut buf[20..24], value);
+    f.write_all(&buf)
+}
+
+/// Test whether `code` is set in a kernel bitfield returned by
+/// `get_event_bits`/`get_key_state`.
+pub fn bit_is_set(bits: &[u8], code: u16) -> bool {
+    let byte = code as usize / 8;
+    let bit = code as usize % 8;
+    match bits.get(byte) {
+        Some(b) => (b >> bit) & 1 != 0,
+        None => false,
+    }
+}
+
+/// Put `f` into non-blocking mode, so a `read` on it returns
+/// `WouldBlock` instead of parking the thread, and callers can `poll`
+/// it alongside other fds (e.g. a wake-up pipe).
+pub fn set_nonblocking(f: &File) -> io::Result<()> {
+    let fd = f.as_raw_fd();
+    let flags = unsafe { fcntl(fd, F_GETFL, 0) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let rc = unsafe { fcntl(fd, F_SETFL, flags | O_NONBLOCK) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Create a `pipe(2)` used only to interrupt a blocked `poll`: returns
+/// `(read_fd, write_fd)`. Writing a byte to `write_fd` wakes up anyone
+/// polling `read_fd` for readability.
+pub fn make_wake_pipe() -> io::Result<(i32, i32)> {
+    let mut fds = [0i32; 2];
+    let rc = unsafe { pipe(fds.as_mut_ptr()) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Write a single byte to a wake-pipe's write end, to interrupt a
+/// thread blocked in `wait_readable`.
+pub fn wake(write_fd: i32) {
+    let byte = 1u8;
+    unsafe {
+        write(write_fd, &byte as *const u8, 1);
+    }
+}
+
+/// Close a raw fd created by `make_wake_pipe`.
+pub fn close_fd(fd: i32) {
+    unsafe {
+        close(fd);
+    }
+}
+
+/// Block until at least one of `fds` is readable, and return which ones
+/// are. Used to wait on the device fd and a wake-up pipe together, so
+/// `close()` can interrupt a blocked read thread.
+pub fn wait_readable(fds: &[i32]) -> io::Result<Vec<bool>> {
+    let mut poll_fds: Vec<PollFd> = fds
+        .iter()
+        .map(|&fd| PollFd {
+            fd,
+            events: POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let rc = unsafe { poll(poll_fds.as_mut_ptr(), poll_fds.len() as u64, -1) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(poll_fds.iter().map(|p| (p.revents & POLLIN) != 0).collect())
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+fn ioctl_get<T>(f: &File, request: u64, out: &mut T) -> io::Result<()> {
+    let rc = unsafe { ioctl(f.as_raw_fd(), request, out as *mut T) };
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn ioctl_get_slice(f: &File, request: u64, out: &mut [u8]) -> io::Result<()> {
+    let rc = unsafe { ioctl(f.as_raw_fd(), request, out.as_mut_ptr()) };
+    if rc < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pins the `ABS_*` codes this crate relies on to
+    /// `<linux/input-event-codes.h>`, so a future transcription slip
+    /// (like the one that shipped here) fails the build instead of
+    /// silently breaking shoulder/motion decoding.
+    #[test]
+    fn abs_codes_match_kernel_header() {
+        assert_eq!(ABS_THROTTLE, 0x06);
+        assert_eq!(ABS_RUDDER, 0x07);
+        assert_eq!(ABS_WHEEL, 0x08);
+        assert_eq!(ABS_GAS, 0x09);
+        assert_eq!(ABS_TILT_X, 0x1a);
+        assert_eq!(ABS_TILT_Y, 0x1b);
+        assert_eq!(ABS_TOOL_WIDTH, 0x1c);
+        assert_eq!(ABS_MISC, 0x28);
+    }
+
+    #[test]
+    fn bit_is_set_reads_little_endian_bitfield() {
+        // bit 0 of byte 0, and bit 1 of byte 2.
+        let bits = [0b0000_0001, 0b0000_0000, 0b0000_0010];
+        assert!(bit_is_set(&bits, 0));
+        assert!(!bit_is_set(&bits, 1));
+        assert!(bit_is_set(&bits, 17));
+        // Past the end of the bitfield: treated as not set, not a panic.
+        assert!(!bit_is_set(&bits, 1000));
+    }
+
+    #[test]
+    fn ioc_matches_known_eviocgid_value() {
+        // `EVIOCGID` as defined by `<linux/input.h>`: _IOR('E', 0x02, struct input_id)
+        assert_eq!(eviocgid(), 0x80084502);
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
@@ -0,0 +1,159 @@
+//! # ff
+//!
+//! Output support for the DUALSHOCK3: the two rumble motors, driven
+//! through the evdev force-feedback interface (`EVIOCSFF` + `EV_FF`),
+//! and the four player-indicator LEDs, driven through `EV_LED`.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+use evdev;
+
+// ****************************************************************************
+//
+// Private Types
+//
+// ****************************************************************************
+
+/// `struct ff_effect` as the kernel defines it, specialised to the
+/// `FF_RUMBLE` variant of its trailing union.
+///
+/// The real struct's union is sized for its largest member,
+/// `struct ff_periodic_effect` (which holds a trailing pointer), making
+/// the whole thing 48 bytes on a 64-bit host - not just
+/// `type/id/direction/trigger/replay/rumble`, which only come to 20. The
+/// ioctl number `EVIOCSFF` encodes this size, so `_reserved` pads the
+/// tail of the union out to match it even though nothing here reads or
+/// writes it for `FF_RUMBLE`.
+#[repr(C)]
+struct FfRumbleEffect {
+    effect_type: u16,
+    id: i16,
+    direction: u16,
+    trigger_button: u16,
+    trigger_interval: u16,
+    replay_length: u16,
+    replay_delay: u16,
+    /// Padding so the union starts 8-byte aligned, matching
+    /// `ff_periodic_effect`'s trailing pointer on a 64-bit host.
+    _union_pad: u16,
+    strong_magnitude: u16,
+    weak_magnitude: u16,
+    /// Rest of the union's 32 bytes, unused by `FF_RUMBLE`.
+    _reserved: [u8; 28],
+}
+
+// ****************************************************************************
+//
+// Private Data
+//
+// ****************************************************************************
+
+const FF_RUMBLE: u16 = 0x50;
+
+/// The DUALSHOCK3 reports four player-indicator LEDs, numbered 0..3.
+const LED_COUNT: u16 = 4;
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+/// Upload a rumble effect with the given motor strengths and play it
+/// once for `duration`. `strong`/`weak` are scaled from `0..=255` up to
+/// the `0..=65535` range the kernel's force-feedback core expects.
+pub fn set_rumble(f: &mut fs::File, strong: u8, weak: u8, duration: Duration) -> io::Result<()> {
+    let mut effect = FfRumbleEffect {
+        effect_type: FF_RUMBLE,
+        // -1 asks the kernel to allocate a new effect slot.
+        id: -1,
+        direction: 0,
+        trigger_button: 0,
+        trigger_interval: 0,
+        replay_length: duration.as_millis().min(65535) as u16,
+        replay_delay: 0,
+        _union_pad: 0,
+        strong_magnitude: scale_to_u16(strong),
+        weak_magnitude: scale_to_u16(weak),
+        _reserved: [0u8; 28],
+    };
+    evdev::ioctl_rw(f, eviocsff(), &mut effect)?;
+    // Play it once; the kernel stops it automatically after
+    // `replay_length` milliseconds.
+    evdev::write_event(f, evdev::EV_FF, effect.id as u16, 1)
+}
+
+/// Set all four player LEDs at once from a 4-bit mask (bit 0 = LED 1).
+pub fn set_leds(f: &mut fs::File, mask: u8) -> io::Result<()> {
+    for led in 0..LED_COUNT {
+        let on = (mask >> led) & 1 != 0;
+        evdev::write_event(f, evdev::EV_LED, led, on as i32)?;
+    }
+    Ok(())
+}
+
+/// Light exactly one LED to indicate a player slot, DUALSHOCK3-style
+/// (player 1 lights LED 1, player 2 lights LED 2, and so on).
+pub fn set_player_number(f: &mut fs::File, n: u8) -> io::Result<()> {
+    let mask = if n >= 1 && n <= LED_COUNT as u8 {
+        1 << (n - 1)
+    } else {
+        0
+    };
+    set_leds(f, mask)
+}
+
+// ****************************************************************************
+//
+// Private Functions
+//
+// ****************************************************************************
+
+/// `EVIOCSFF` - upload a force-feedback effect
+fn eviocsff() -> u64 {
+    evdev::ioc(
+        evdev::IOC_WRITE,
+        evdev::EV_IOC_MAGIC,
+        0x80,
+        ::std::mem::size_of::<FfRumbleEffect>() as u32,
+    )
+}
+
+fn scale_to_u16(value: u8) -> u16 {
+    (value as u16) << 8 | value as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pins `FfRumbleEffect`'s size and `eviocsff()` against the real
+    /// `EVIOCSFF = 0x40304580` from `<linux/input.h>`, the same way
+    /// `evdev.rs`'s `ioc_matches_known_eviocgid_value` pins `EVIOCGID`.
+    #[test]
+    fn eviocsff_matches_kernel_constant() {
+        assert_eq!(::std::mem::size_of::<FfRumbleEffect>(), 48);
+        assert_eq!(eviocsff(), 0x40304580);
+    }
+
+    #[test]
+    fn scale_to_u16_hits_both_ends() {
+        assert_eq!(scale_to_u16(0), 0);
+        assert_eq!(scale_to_u16(255), 0xffff);
+        assert_eq!(scale_to_u16(1), 0x0101);
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************
@@ -8,15 +8,33 @@
 // ****************************************************************************
 
 extern crate byteorder;
+#[cfg(feature = "tokio")]
+extern crate futures_core;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+
+mod dsu;
+mod evdev;
+mod ff;
+#[cfg(feature = "tokio")]
+mod stream;
+
+pub use dsu::MotionServer;
+#[cfg(feature = "tokio")]
+pub use stream::EventStream;
 
 use std::collections::HashMap;
 use std::fs;
 use std::io::prelude::*;
 use std::path;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-use byteorder::{ByteOrder, NativeEndian};
+use evdev::{AbsInfo, InputId, RawEvent};
 
 // ****************************************************************************
 //
@@ -74,16 +92,109 @@ pub enum Button {
     R2,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+/// Represents the SIXAXIS/DUALSHOCK3 motion sensors.
+///
+/// The SIXAXIS has a three-axis accelerometer; the DUALSHOCK3 adds a
+/// single-axis gyro that measures yaw rate. Raw values are reported in
+/// the device's native units (roughly 113 counts/g for the accelerometer
+/// axes, and an implementation-defined scale for the gyro) - use
+/// `read_motion` and divide by the appropriate constant to get g /
+/// degrees-per-second if you need physical units.
+pub enum Motion {
+    /// Acceleration along the controller's X axis (left/right), in
+    /// raw counts. Roughly 113 counts/g.
+    AccelX,
+    /// Acceleration along the controller's Y axis (forward/back), in
+    /// raw counts. Roughly 113 counts/g.
+    AccelY,
+    /// Acceleration along the controller's Z axis (up/down), in raw
+    /// counts. Roughly 113 counts/g.
+    AccelZ,
+    /// Yaw rotation rate, in raw counts. Only present on the
+    /// DUALSHOCK3's gyro, not the original SIXAXIS.
+    GyroYaw,
+}
+
+/// Every `Axis` variant, for iterating/probing capabilities.
+const ALL_AXES: [Axis; 4] = [Axis::LX, Axis::LY, Axis::RX, Axis::RY];
+
+/// Every `Motion` variant, for iterating/probing capabilities.
+const ALL_MOTIONS: [Motion; 4] = [Motion::AccelX, Motion::AccelY, Motion::AccelZ, Motion::GyroYaw];
+
+/// One decoded input event, as produced by the read thread and
+/// delivered to `SixAxis::next_event` (or `EventStream`, with the
+/// `tokio` feature enabled).
+#[derive(Debug, Copy, Clone)]
+pub enum Event {
+    Axis(Axis, i16),
+    Shoulder(Shoulder, u16),
+    Button(Button, bool),
+    Motion(Motion, i16),
+}
+
+/// Every `Shoulder` variant, for iterating/probing capabilities.
+const ALL_SHOULDERS: [Shoulder; 4] = [Shoulder::L1, Shoulder::L2, Shoulder::R1, Shoulder::R2];
+
+/// Every `Button` variant, for iterating/probing capabilities.
+const ALL_BUTTONS: [Button; 17] = [
+    Button::Square,
+    Button::Circle,
+    Button::Triangle,
+    Button::Cross,
+    Button::PS,
+    Button::Start,
+    Button::Select,
+    Button::LeftStick,
+    Button::RightStick,
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::L1,
+    Button::L2,
+    Button::R1,
+    Button::R2,
+];
+
+/// The kernel's reported range for one analog axis, used to normalize raw
+/// `EV_ABS` values onto the ranges this crate promises callers.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct AxisRange {
+    minimum: i32,
+    maximum: i32,
+}
+
+impl AxisRange {
+    fn from_abs_info(info: &AbsInfo) -> AxisRange {
+        AxisRange {
+            minimum: info.minimum,
+            maximum: info.maximum,
+        }
+    }
+
+    /// Normalize a raw value onto -32768..32767.
+    fn normalize_i16(&self, value: i32) -> i16 {
+        normalize(value, self.minimum, self.maximum, -32768, 32767) as i16
+    }
+
+    /// Normalize a raw value onto 0..65535.
+    fn normalize_u16(&self, value: i32) -> u16 {
+        normalize(value, self.minimum, self.maximum, 0, 65535) as u16
+    }
+}
+
 /// Represents the current state of the SIXAXIS controller, including
 /// the position of all analog axes and the state of all digital buttons.
 pub struct State {
     axes: HashMap<Axis, i16>,
     shoulders: HashMap<Shoulder, u16>,
     buttons: HashMap<Button, bool>,
+    motions: HashMap<Motion, i16>,
 }
 
 /// Represents a DUALSHOCK3/SIXAXIS controller connected
-/// as a Linux input device (e.g. /dev/input/js0)
+/// as a Linux input device (e.g. /dev/input/event3)
 pub struct SixAxis {
     /// Path we opened (for debug)
     path: path::PathBuf,
@@ -91,6 +202,21 @@ pub struct SixAxis {
     state: Arc<Mutex<State>>,
     /// The read thread, which blocks on the event
     child: Option<thread::JoinHandle<()>>,
+    /// Every decoded `Event` is pushed here once something has actually
+    /// called `next_event`, so callers can block on it instead of
+    /// polling the state snapshot. Left `None` until the first call, so
+    /// callers who only ever use `read_axis`/`read_button`/etc don't pay
+    /// for an mpsc channel nothing drains.
+    events: Mutex<Option<mpsc::Receiver<Event>>>,
+    /// The sending half of `events`, shared with the read thread. `None`
+    /// until `next_event` creates the channel; the read thread checks
+    /// this on every decoded event instead of sending unconditionally.
+    event_tx: Arc<Mutex<Option<mpsc::Sender<Event>>>>,
+    /// Set by `close()` to tell the read thread to stop.
+    shutdown: Arc<AtomicBool>,
+    /// Write end of the pipe `close()` uses to wake the read thread out
+    /// of its blocking `poll`.
+    wake_write_fd: Option<i32>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -119,11 +245,7 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 //
 // ****************************************************************************
 
-enum Event {
-    Axis(Axis, i16),
-    Shoulder(Shoulder, u16),
-    Button(Button, bool)
-}
+// None
 
 // ****************************************************************************
 //
@@ -131,8 +253,12 @@ enum Event {
 //
 // ****************************************************************************
 
-const EVENT_SIZE:usize = 8;
-const VERBOSE:bool = false;
+const VERBOSE: bool = false;
+
+/// Sony's USB vendor id, as reported by `EVIOCGID`.
+const SONY_VENDOR_ID: u16 = 0x054c;
+/// The SIXAXIS/DUALSHOCK3's USB product id. Both pads share one id.
+const SIXAXIS_PRODUCT_ID: u16 = 0x0268;
 
 // ****************************************************************************
 //
@@ -140,9 +266,33 @@ const VERBOSE:bool = false;
 //
 // ****************************************************************************
 
+/// Scan `/dev/input` for every evdev node that looks like a
+/// SIXAXIS/DUALSHOCK3 (matched by USB vendor/product id, falling back to
+/// the device name), and return an unopened `SixAxis` for each.
+///
+/// This lets an application pick "player 1 / player 2" from whatever
+/// pads happen to be plugged in, rather than guessing device paths.
+pub fn enumerate() -> impl Iterator<Item = SixAxis> {
+    let entries = fs::read_dir("/dev/input").into_iter().flatten();
+    entries.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        let is_event_node = path
+            .file_name()?
+            .to_str()?
+            .starts_with("event");
+        if !is_event_node {
+            return None;
+        }
+        let f = fs::File::open(&path).ok()?;
+        if is_sixaxis(&f) {
+            Some(SixAxis::new(path))
+        } else {
+            None
+        }
+    })
+}
 
 impl SixAxis {
-
     /// Create a new SixAxis object, but don't open the file
     /// just yet.
     pub fn new<P: AsRef<path::Path>>(path: P) -> SixAxis {
@@ -150,80 +300,199 @@ impl SixAxis {
         SixAxis {
             path: path::PathBuf::from(path.as_ref()),
             state: Arc::new(Mutex::new(State::new())),
-            child: None
+            child: None,
+            events: Mutex::new(None),
+            event_tx: Arc::new(Mutex::new(None)),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            wake_write_fd: None,
         }
     }
 
     /// Actually open the path to the controller.
+    ///
+    /// This also queries the device's `EVIOCGABS` ranges for each axis
+    /// we understand, so raw values can be normalized as they arrive.
     pub fn open(&mut self) -> Result<()> {
+        if self.child.is_some() {
+            return Err(Error::AlreadyOpen);
+        }
+
         // Open the file.
         // This is moved to the thread.
-        let mut f = fs::File::open(&self.path)?;
+        let f = fs::File::open(&self.path)?;
+
+        // Work out the real min/max for each axis up-front, rather than
+        // assuming -32768..32767 like the old joydev code did.
+        let axis_ranges = read_axis_ranges(&f)?;
+        let shoulder_ranges = read_shoulder_ranges(&f)?;
+
+        // Non-blocking, so the thread can `poll` the device fd alongside
+        // the wake-up pipe instead of parking forever in `read`.
+        evdev::set_nonblocking(&f)?;
+        let (wake_read_fd, wake_write_fd) = evdev::make_wake_pipe()?;
+        self.wake_write_fd = Some(wake_write_fd);
+        self.shutdown.store(false, Ordering::SeqCst);
+        let shutdown = self.shutdown.clone();
+
         // Clone the Arc holding the state.
         // This is moved to the thread.
         let state_ref = self.state.clone();
+        // Reset from any previous `open()`; `next_event` (re-)creates
+        // the channel on first use.
+        *self.events.lock().unwrap() = None;
+        *self.event_tx.lock().unwrap() = None;
+        let event_tx = self.event_tx.clone();
         // Make the thread to read the file in a blocking fashion
         self.child = Some(thread::spawn(move || {
-            loop {
-                let mut buf = [0u8; EVENT_SIZE];
+            let mut f = f;
+            let device_fd = f.as_raw_fd();
+            'outer: loop {
+                // Block until the device has something to read, or
+                // `close()` wrote to the wake pipe.
+                match evdev::wait_readable(&[device_fd, wake_read_fd]) {
+                    Ok(ready) if ready[1] || shutdown.load(Ordering::SeqCst) => break,
+                    Ok(ready) if !ready[0] => continue,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+
+                let mut buf = [0u8; evdev::INPUT_EVENT_SIZE];
                 match f.read_exact(&mut buf) {
                     Ok(_) => {
-                        let ev = process_event(&buf);
-                        let mut state = state_ref.lock().unwrap();
-                        match ev {
-                            Ok(Event::Axis(axis, value)) => { state.axes.insert(axis, value); }
-                            Ok(Event::Shoulder(shoulder, value)) => { state.shoulders.insert(shoulder, value); }
-                            Ok(Event::Button(button, value)) => { state.buttons.insert(button, value); }
-                            // Drop event
-                            Err(_) => {},
+                        let raw = evdev::parse_event(&buf);
+                        if raw.ev_type == evdev::EV_SYN && raw.code == evdev::SYN_DROPPED {
+                            // The kernel's event buffer overflowed and it
+                            // skipped straight to here; `state` may now be
+                            // stale. Re-read everything and emit synthetic
+                            // events for whatever actually changed.
+                            if let Ok(events) =
+                                resync(&f, &axis_ranges, &shoulder_ranges, &state_ref)
+                            {
+                                for ev in events {
+                                    send_event(&event_tx, ev);
+                                }
+                            }
+                            continue;
+                        }
+                        if let Some(ev) = process_event(&raw, &axis_ranges, &shoulder_ranges) {
+                            apply_event(&state_ref, ev);
+                            send_event(&event_tx, ev);
                         }
                     }
+                    // Non-blocking reads can race the wake-up poll and
+                    // come back empty; just go round and poll again.
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => continue 'outer,
                     Err(_) => break,
                 };
             }
-            println!("Bluetooth read thread exited!");
+            // Drop the sender so a caller blocked in `next_event`'s
+            // `recv()` wakes up with an error instead of hanging forever -
+            // in particular, `close()` must never need to take `events`'s
+            // lock for this, since `next_event` holds that lock for the
+            // entire duration of its blocking `recv()`.
+            *event_tx.lock().unwrap() = None;
+            evdev::close_fd(wake_read_fd);
+            if VERBOSE {
+                println!("evdev read thread exited!");
+            }
         }));
         Ok(())
     }
 
     /// Close the controller.
     ///
-    /// Can call `open` later, if required.
+    /// Wakes the read thread, waits for it to exit, and releases the
+    /// underlying file descriptors. Can call `open` later, if required.
     pub fn close(&mut self) -> Result<()> {
-        match self.child {
+        match self.child.take() {
             None => Err(Error::NotOpen),
-            Some(ref th) => {
-                // Kill the thread
-                Err(Error::NotImplemented)
+            Some(handle) => {
+                self.shutdown.store(true, Ordering::SeqCst);
+                if let Some(fd) = self.wake_write_fd.take() {
+                    evdev::wake(fd);
+                    evdev::close_fd(fd);
+                }
+                *self.events.lock().unwrap() = None;
+                *self.event_tx.lock().unwrap() = None;
+                handle.join().map_err(|_| Error::UnknownError)
             }
         }
     }
 
+    /// Which axes this device reports, as queried from the kernel via
+    /// `EVIOCGBIT(EV_ABS, ...)`.
+    pub fn supported_axes(&self) -> Result<Vec<Axis>> {
+        let f = fs::File::open(&self.path)?;
+        let bits = evdev::get_event_bits(&f, evdev::EV_ABS)?;
+        Ok(ALL_AXES
+            .iter()
+            .cloned()
+            .filter(|a| evdev::bit_is_set(&bits, axis_to_code(*a)))
+            .collect())
+    }
+
+    /// Which shoulder buttons this device reports, as queried from the
+    /// kernel via `EVIOCGBIT(EV_ABS, ...)`.
+    pub fn supported_shoulders(&self) -> Result<Vec<Shoulder>> {
+        let f = fs::File::open(&self.path)?;
+        let bits = evdev::get_event_bits(&f, evdev::EV_ABS)?;
+        Ok(ALL_SHOULDERS
+            .iter()
+            .cloned()
+            .filter(|s| evdev::bit_is_set(&bits, shoulder_to_code(*s)))
+            .collect())
+    }
+
+    /// Which digital buttons this device reports, as queried from the
+    /// kernel via `EVIOCGBIT(EV_KEY, ...)`.
+    pub fn supported_buttons(&self) -> Result<Vec<Button>> {
+        let f = fs::File::open(&self.path)?;
+        let bits = evdev::get_event_bits(&f, evdev::EV_KEY)?;
+        Ok(ALL_BUTTONS
+            .iter()
+            .cloned()
+            .filter(|b| evdev::bit_is_set(&bits, button_to_code(*b)))
+            .collect())
+    }
+
+    /// Which motion sensors this device reports, as queried from the
+    /// kernel via `EVIOCGBIT(EV_ABS, ...)`. A plain SIXAXIS will report
+    /// the three accelerometer axes but not the gyro.
+    pub fn supported_motions(&self) -> Result<Vec<Motion>> {
+        let f = fs::File::open(&self.path)?;
+        let bits = evdev::get_event_bits(&f, evdev::EV_ABS)?;
+        Ok(ALL_MOTIONS
+            .iter()
+            .cloned()
+            .filter(|m| evdev::bit_is_set(&bits, motion_to_code(*m)))
+            .collect())
+    }
+
     /// Read a thumb-stick axis.
     ///
-    /// Returns the most recent value from the controller.
-    /// The thumb sticks are -32768..+32767. Returns 0
-    /// if the axis has never reported itself.
+    /// Returns the most recent value from the controller, normalized
+    /// onto -32768..+32767 using the device's own reported range.
+    /// Returns 0 if the axis has never reported itself.
     pub fn read_axis(&self, axis: Axis) -> Result<i16> {
         // Return error if thread is dead
         let state = self.state.lock().unwrap();
         match state.axes.get(&axis) {
             Some(value) => Ok(*value),
-            None => Ok(0)
+            None => Ok(0),
         }
     }
 
     /// Read an analog shoulder button.
     ///
-    /// Returns the most recent value from the controller.
-    /// The shoulder buttons are 0..65535. Returns 0
-    /// if the shoulder has never reported itself.
+    /// Returns the most recent value from the controller, normalized
+    /// onto 0..65535 using the device's own reported range.
+    /// Returns 0 if the shoulder has never reported itself.
     pub fn read_shoulder(&self, shoulder: Shoulder) -> Result<u16> {
         // Return error if thread is dead
         let state = self.state.lock().unwrap();
         match state.shoulders.get(&shoulder) {
             Some(value) => Ok(*value),
-            None => Ok(0)
+            None => Ok(0),
         }
     }
 
@@ -237,8 +506,96 @@ impl SixAxis {
         let state = self.state.lock().unwrap();
         match state.buttons.get(&button) {
             Some(value) => Ok(*value),
-            None => Ok(false)
+            None => Ok(false),
+        }
+    }
+
+    /// Read a motion sensor (accelerometer or gyro).
+    ///
+    /// Returns the most recent raw value from the controller. See
+    /// `Motion` for the approximate physical scaling of each axis.
+    /// Returns 0 if the sensor has never reported itself.
+    pub fn read_motion(&self, motion: Motion) -> Result<i16> {
+        // Only reads the shared snapshot; this never returns an error
+        let state = self.state.lock().unwrap();
+        match state.motions.get(&motion) {
+            Some(value) => Ok(*value),
+            None => Ok(0),
+        }
+    }
+
+    /// The device's human-readable name, e.g.
+    /// `"Sony PLAYSTATION(R)3 Controller"`, for telling multiple
+    /// connected pads apart.
+    pub fn name(&self) -> Result<String> {
+        let f = fs::File::open(&self.path)?;
+        Ok(evdev::get_name(&f)?)
+    }
+
+    /// The device's USB vendor/product/bustype/version, for telling
+    /// multiple connected pads apart.
+    pub fn input_id(&self) -> Result<InputId> {
+        let f = fs::File::open(&self.path)?;
+        Ok(evdev::get_input_id(&f)?)
+    }
+
+    /// Rumble the DUALSHOCK3's two motors for `duration`.
+    ///
+    /// `strong`/`weak` are `0..=255`, scaled up to the kernel's native
+    /// `0..=65535` force-feedback magnitude range. Returns as soon as
+    /// the effect has been uploaded and started playing; the motors
+    /// keep running in the background for `duration`.
+    pub fn set_rumble(&self, strong: u8, weak: u8, duration: Duration) -> Result<()> {
+        let mut f = fs::OpenOptions::new().read(true).write(true).open(&self.path)?;
+        ff::set_rumble(&mut f, strong, weak, duration)?;
+        // The kernel owns uploaded FF effects by the file description
+        // that created them, and erases/stops them the moment that fd
+        // is closed (`input_ff_flush` on release). Hold `f` open on a
+        // background thread for `duration` so the effect actually gets
+        // to play, instead of dropping it - and cutting the rumble off
+        // - within microseconds of starting it.
+        thread::spawn(move || {
+            thread::sleep(duration);
+            drop(f);
+        });
+        Ok(())
+    }
+
+    /// Set all four player-indicator LEDs at once, one bit per LED
+    /// (bit 0 = LED 1).
+    pub fn set_leds(&self, mask: u8) -> Result<()> {
+        let mut f = fs::OpenOptions::new().read(true).write(true).open(&self.path)?;
+        Ok(ff::set_leds(&mut f, mask)?)
+    }
+
+    /// Light exactly one LED to indicate a player slot (1-4),
+    /// DUALSHOCK3-style.
+    pub fn set_player_number(&self, n: u8) -> Result<()> {
+        let mut f = fs::OpenOptions::new().read(true).write(true).open(&self.path)?;
+        Ok(ff::set_player_number(&mut f, n)?)
+    }
+
+    /// Block until the read thread decodes the next `Event`.
+    ///
+    /// This is an alternative to polling `read_axis`/`read_button`/etc,
+    /// for callers that want to react to edges (e.g. "button just
+    /// pressed") rather than just sample the latest state.
+    ///
+    /// The underlying channel is created lazily, on the first call to
+    /// `next_event`: callers who never call this only ever pay for the
+    /// state snapshot, and the read thread never queues events nobody's
+    /// going to drain.
+    pub fn next_event(&self) -> Result<Event> {
+        if self.child.is_none() {
+            return Err(Error::NotOpen);
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.is_none() {
+            let (tx, rx) = mpsc::channel();
+            *self.event_tx.lock().unwrap() = Some(tx);
+            *events = Some(rx);
         }
+        events.as_ref().unwrap().recv().map_err(|_| Error::IOError)
     }
 }
 
@@ -248,68 +605,302 @@ impl ::std::fmt::Debug for SixAxis {
     }
 }
 
+impl Drop for SixAxis {
+    fn drop(&mut self) {
+        // Ignore the result: if we were never opened, or already
+        // closed, there's nothing left to clean up.
+        let _ = self.close();
+    }
+}
+
 // ****************************************************************************
 //
 // Private Functions
 //
 // ****************************************************************************
 
-const EVENT_TYPE_BUTTON:u8 = 1;
-const EVENT_TYPE_STICK:u8 = 2;
-const EVENT_TYPE_INIT:u8 = 128;
-const EVENT_TYPE_INITBUTTON:u8 = 129;
-const EVENT_TYPE_INITSTICK:u8 = 130;
-
-const EVENT_STICK_IDX_LX:u8 = 0;
-const EVENT_STICK_IDX_LY:u8 = 1;
-const EVENT_STICK_IDX_RX:u8 = 2;
-const EVENT_STICK_IDX_RY:u8 = 3;
-const EVENT_STICK_IDX_L2:u8 = 12;
-const EVENT_STICK_IDX_R2:u8 = 13;
-const EVENT_STICK_IDX_L1:u8 = 14;
-const EVENT_STICK_IDX_R1:u8 = 15;
-
-const EVENT_BUTTON_IDX_SELECT:u8 = 0;
-const EVENT_BUTTON_IDX_LEFTSTICK:u8 = 1;
-const EVENT_BUTTON_IDX_RIGHTSTICK:u8 = 2;
-const EVENT_BUTTON_IDX_START:u8 = 3;
-const EVENT_BUTTON_IDX_UP:u8 = 4;
-const EVENT_BUTTON_IDX_RIGHT:u8 = 5;
-const EVENT_BUTTON_IDX_DOWN:u8 = 6;
-const EVENT_BUTTON_IDX_LEFT:u8 = 7;
-const EVENT_BUTTON_IDX_L2:u8 = 8;
-const EVENT_BUTTON_IDX_R2:u8 = 9;
-const EVENT_BUTTON_IDX_L1:u8 = 10;
-const EVENT_BUTTON_IDX_R1:u8 = 11;
-const EVENT_BUTTON_IDX_PS:u8 = 16;
-const EVENT_BUTTON_IDX_TRIANGLE:u8 = 12;
-const EVENT_BUTTON_IDX_CIRCLE:u8 = 13;
-const EVENT_BUTTON_IDX_CROSS:u8 = 14;
-const EVENT_BUTTON_IDX_SQUARE:u8 = 15;
-
-fn process_event(buf: &[u8; EVENT_SIZE]) -> Result<Event> {
-    let timestamp = NativeEndian::read_u32(&buf[0..3]);
-    let value = NativeEndian::read_u16(&buf[4..5]);
-    let ev_type = buf[6];
-    let ev_idx = buf[7];
-    match ev_type {
-        EVENT_TYPE_STICK | EVENT_TYPE_INITSTICK => process_stick(ev_idx, value),
-        EVENT_TYPE_BUTTON | EVENT_TYPE_INITBUTTON => process_button(ev_idx, value),
-        _ => Err(Error::UnknownError),
-    }
-}
-
-fn process_stick(ev_idx: u8, value: u16) -> Result<Event> {
-    let s_val:i16 = if (value & 0x8000) != 0 {
-        ((value as i32) - 65536) as i16
-    } else {
-        value as i16
-    };
-    Ok(Event::Axis(Axis::LX, 0))
-}
-
-fn process_button(ev_idx: u8, value: u16) -> Result<Event> {
-    Ok(Event::Button(Button::Start, false))
+/// Does `f` look like a SIXAXIS/DUALSHOCK3? Matched primarily by USB
+/// vendor/product id, falling back to the device name for pads
+/// connected through something that doesn't preserve it (e.g. some
+/// Bluetooth stacks).
+fn is_sixaxis(f: &fs::File) -> bool {
+    if let Ok(id) = evdev::get_input_id(f) {
+        if id.vendor == SONY_VENDOR_ID && id.product == SIXAXIS_PRODUCT_ID {
+            return true;
+        }
+    }
+    if let Ok(name) = evdev::get_name(f) {
+        return name.contains("PLAYSTATION(R)3") || name.contains("SIXAXIS");
+    }
+    false
+}
+
+/// Re-read the device's complete current state (every key and every
+/// axis we understand) and diff it against `state`, emitting a synthetic
+/// `Event` for each control that changed while we weren't looking.
+///
+/// This is the recovery path for `SYN_DROPPED`: the kernel only tells us
+/// its buffer overflowed, not what we missed, so the only way back to a
+/// consistent view is to ask it for everything and compare.
+fn resync(
+    f: &fs::File,
+    axis_ranges: &HashMap<Axis, AxisRange>,
+    shoulder_ranges: &HashMap<Shoulder, AxisRange>,
+    state: &Arc<Mutex<State>>,
+) -> Result<Vec<Event>> {
+    let key_bits = evdev::get_key_state(f)?;
+    let mut fresh = State::new();
+
+    for axis in ALL_AXES.iter().cloned() {
+        if let Ok(info) = evdev::get_abs_info(f, axis_to_code(axis)) {
+            let norm = match axis_ranges.get(&axis) {
+                Some(range) => range.normalize_i16(info.value),
+                None => info.value as i16,
+            };
+            fresh.axes.insert(axis, norm);
+        }
+    }
+    for shoulder in ALL_SHOULDERS.iter().cloned() {
+        if let Ok(info) = evdev::get_abs_info(f, shoulder_to_code(shoulder)) {
+            let norm = match shoulder_ranges.get(&shoulder) {
+                Some(range) => range.normalize_u16(info.value),
+                None => info.value as u16,
+            };
+            fresh.shoulders.insert(shoulder, norm);
+        }
+    }
+    for motion in ALL_MOTIONS.iter().cloned() {
+        if let Ok(info) = evdev::get_abs_info(f, motion_to_code(motion)) {
+            fresh.motions.insert(motion, info.value as i16);
+        }
+    }
+    for button in ALL_BUTTONS.iter().cloned() {
+        fresh
+            .buttons
+            .insert(button, evdev::bit_is_set(&key_bits, button_to_code(button)));
+    }
+
+    let mut current = state.lock().unwrap();
+    Ok(diff_state(&fresh, &mut current))
+}
+
+/// Fold `fresh` into `current`, returning a synthetic `Event` for each
+/// control whose value actually changed.
+///
+/// `current.*.insert` returns the previous value, if any - `None` means
+/// this is the first time we've ever recorded this key (e.g. the very
+/// first resync after `open()`, before any real event has arrived), in
+/// which case we just seed `current` and don't synthesize a change the
+/// wire never actually reported.
+fn diff_state(fresh: &State, current: &mut State) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    for axis in ALL_AXES.iter().cloned() {
+        let new_value = *fresh.axes.get(&axis).unwrap_or(&0);
+        if let Some(old_value) = current.axes.insert(axis, new_value) {
+            if old_value != new_value {
+                events.push(Event::Axis(axis, new_value));
+            }
+        }
+    }
+    for shoulder in ALL_SHOULDERS.iter().cloned() {
+        let new_value = *fresh.shoulders.get(&shoulder).unwrap_or(&0);
+        if let Some(old_value) = current.shoulders.insert(shoulder, new_value) {
+            if old_value != new_value {
+                events.push(Event::Shoulder(shoulder, new_value));
+            }
+        }
+    }
+    for motion in ALL_MOTIONS.iter().cloned() {
+        let new_value = *fresh.motions.get(&motion).unwrap_or(&0);
+        if let Some(old_value) = current.motions.insert(motion, new_value) {
+            if old_value != new_value {
+                events.push(Event::Motion(motion, new_value));
+            }
+        }
+    }
+    for button in ALL_BUTTONS.iter().cloned() {
+        let new_value = *fresh.buttons.get(&button).unwrap_or(&false);
+        if let Some(old_value) = current.buttons.insert(button, new_value) {
+            if old_value != new_value {
+                events.push(Event::Button(button, new_value));
+            }
+        }
+    }
+
+    events
+}
+
+/// Send `ev` down the `next_event` channel, if anyone has actually
+/// called `next_event` to create it. A no-op otherwise, so the read
+/// thread never force-feeds a channel nobody's draining.
+fn send_event(event_tx: &Arc<Mutex<Option<mpsc::Sender<Event>>>>, ev: Event) {
+    if let Some(tx) = event_tx.lock().unwrap().as_ref() {
+        // Ignore the error: it just means every `SixAxis` that could
+        // receive has been dropped.
+        let _ = tx.send(ev);
+    }
+}
+
+/// Fold one decoded `Event` into the shared `State` under its lock.
+fn apply_event(state: &Arc<Mutex<State>>, ev: Event) {
+    let mut state = state.lock().unwrap();
+    match ev {
+        Event::Axis(axis, value) => {
+            state.axes.insert(axis, value);
+        }
+        Event::Shoulder(shoulder, value) => {
+            state.shoulders.insert(shoulder, value);
+        }
+        Event::Button(button, value) => {
+            state.buttons.insert(button, value);
+        }
+        Event::Motion(motion, value) => {
+            state.motions.insert(motion, value);
+        }
+    }
+}
+
+fn axis_to_code(axis: Axis) -> u16 {
+    match axis {
+        Axis::LX => evdev::ABS_X,
+        Axis::LY => evdev::ABS_Y,
+        Axis::RX => evdev::ABS_RX,
+        Axis::RY => evdev::ABS_RY,
+    }
+}
+
+fn shoulder_to_code(shoulder: Shoulder) -> u16 {
+    // The DUALSHOCK3 reports the analog shoulders as extra absolute axes
+    // beyond the four thumb-stick ones.
+    match shoulder {
+        Shoulder::L2 => evdev::ABS_THROTTLE,
+        Shoulder::R2 => evdev::ABS_RUDDER,
+        Shoulder::L1 => evdev::ABS_WHEEL,
+        Shoulder::R1 => evdev::ABS_GAS,
+    }
+}
+
+fn motion_to_code(motion: Motion) -> u16 {
+    // The accelerometer/gyro share the ABS_* code space with the sticks
+    // and shoulders, but the DUALSHOCK3 reports them on axes those
+    // controls don't use.
+    match motion {
+        Motion::AccelX => evdev::ABS_TILT_X,
+        Motion::AccelY => evdev::ABS_TILT_Y,
+        Motion::AccelZ => evdev::ABS_TOOL_WIDTH,
+        Motion::GyroYaw => evdev::ABS_MISC,
+    }
+}
+
+fn button_to_code(button: Button) -> u16 {
+    match button {
+        Button::Square => evdev::BTN_WEST,
+        Button::Circle => evdev::BTN_EAST,
+        Button::Triangle => evdev::BTN_NORTH,
+        Button::Cross => evdev::BTN_SOUTH,
+        Button::PS => evdev::BTN_MODE,
+        Button::Start => evdev::BTN_START,
+        Button::Select => evdev::BTN_SELECT,
+        Button::LeftStick => evdev::BTN_THUMBL,
+        Button::RightStick => evdev::BTN_THUMBR,
+        Button::Up => evdev::BTN_DPAD_UP,
+        Button::Down => evdev::BTN_DPAD_DOWN,
+        Button::Left => evdev::BTN_DPAD_LEFT,
+        Button::Right => evdev::BTN_DPAD_RIGHT,
+        Button::L1 => evdev::BTN_TL,
+        Button::L2 => evdev::BTN_TL2,
+        Button::R1 => evdev::BTN_TR,
+        Button::R2 => evdev::BTN_TR2,
+    }
+}
+
+/// Query `EVIOCGABS` for every `Axis` the device supports.
+pub(crate) fn read_axis_ranges(f: &fs::File) -> Result<HashMap<Axis, AxisRange>> {
+    let mut ranges = HashMap::new();
+    for axis in ALL_AXES.iter().cloned() {
+        if let Ok(info) = evdev::get_abs_info(f, axis_to_code(axis)) {
+            ranges.insert(axis, AxisRange::from_abs_info(&info));
+        }
+    }
+    Ok(ranges)
+}
+
+/// Query `EVIOCGABS` for every `Shoulder` the device supports.
+pub(crate) fn read_shoulder_ranges(f: &fs::File) -> Result<HashMap<Shoulder, AxisRange>> {
+    let mut ranges = HashMap::new();
+    for shoulder in ALL_SHOULDERS.iter().cloned() {
+        if let Ok(info) = evdev::get_abs_info(f, shoulder_to_code(shoulder)) {
+            ranges.insert(shoulder, AxisRange::from_abs_info(&info));
+        }
+    }
+    Ok(ranges)
+}
+
+pub(crate) fn process_event(
+    raw: &RawEvent,
+    axis_ranges: &HashMap<Axis, AxisRange>,
+    shoulder_ranges: &HashMap<Shoulder, AxisRange>,
+) -> Option<Event> {
+    match raw.ev_type {
+        evdev::EV_ABS => process_abs(raw.code, raw.value, axis_ranges, shoulder_ranges),
+        evdev::EV_KEY => process_key(raw.code, raw.value),
+        _ => None,
+    }
+}
+
+fn process_abs(
+    code: u16,
+    value: i32,
+    axis_ranges: &HashMap<Axis, AxisRange>,
+    shoulder_ranges: &HashMap<Shoulder, AxisRange>,
+) -> Option<Event> {
+    for axis in ALL_AXES.iter().cloned() {
+        if axis_to_code(axis) == code {
+            let norm = match axis_ranges.get(&axis) {
+                Some(range) => range.normalize_i16(value),
+                None => value as i16,
+            };
+            return Some(Event::Axis(axis, norm));
+        }
+    }
+    for shoulder in ALL_SHOULDERS.iter().cloned() {
+        if shoulder_to_code(shoulder) == code {
+            let norm = match shoulder_ranges.get(&shoulder) {
+                Some(range) => range.normalize_u16(value),
+                None => value as u16,
+            };
+            return Some(Event::Shoulder(shoulder, norm));
+        }
+    }
+    for motion in ALL_MOTIONS.iter().cloned() {
+        if motion_to_code(motion) == code {
+            // Motion sensors are reported in their own raw units (see
+            // `Motion`'s doc comment), not normalized like the sticks.
+            return Some(Event::Motion(motion, value as i16));
+        }
+    }
+    None
+}
+
+fn process_key(code: u16, value: i32) -> Option<Event> {
+    for button in ALL_BUTTONS.iter().cloned() {
+        if button_to_code(button) == code {
+            return Some(Event::Button(button, value != 0));
+        }
+    }
+    None
+}
+
+/// Linearly rescale `value` from `[in_min, in_max]` to `[out_min, out_max]`.
+fn normalize(value: i32, in_min: i32, in_max: i32, out_min: i32, out_max: i32) -> i32 {
+    if in_max <= in_min {
+        return out_min;
+    }
+    let span = (in_max - in_min) as i64;
+    let out_span = (out_max - out_min) as i64;
+    let scaled = (value - in_min) as i64 * out_span / span + out_min as i64;
+    scaled.max(out_min as i64).min(out_max as i64) as i32
 }
 
 impl State {
@@ -318,6 +909,7 @@ impl State {
             axes: HashMap::new(),
             shoulders: HashMap::new(),
             buttons: HashMap::new(),
+            motions: HashMap::new(),
         }
     }
 }
@@ -328,6 +920,84 @@ impl From<::std::io::Error> for Error {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_rescales_and_clamps() {
+        assert_eq!(normalize(0, -128, 127, -32768, 32767), 128);
+        assert_eq!(normalize(-128, -128, 127, -32768, 32767), -32768);
+        assert_eq!(normalize(127, -128, 127, -32768, 32767), 32767);
+        // Past the reported maximum: clamped to the top of the output range.
+        assert_eq!(normalize(200, -128, 127, -32768, 32767), 32767);
+        // Degenerate range: always the low end, never a divide-by-zero.
+        assert_eq!(normalize(5, 10, 10, 0, 65535), 0);
+    }
+
+    #[test]
+    fn process_event_maps_abs_and_key_codes() {
+        let axis_ranges = HashMap::new();
+        let shoulder_ranges = HashMap::new();
+
+        let abs = RawEvent {
+            ev_type: evdev::EV_ABS,
+            code: evdev::ABS_X,
+            value: 100,
+        };
+        match process_event(&abs, &axis_ranges, &shoulder_ranges) {
+            Some(Event::Axis(Axis::LX, 100)) => {}
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        let key = RawEvent {
+            ev_type: evdev::EV_KEY,
+            code: evdev::BTN_SOUTH,
+            value: 1,
+        };
+        match process_event(&key, &axis_ranges, &shoulder_ranges) {
+            Some(Event::Button(Button::Cross, true)) => {}
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        // Neither a code we map nor EV_SYN: ignored.
+        let unknown = RawEvent {
+            ev_type: evdev::EV_ABS,
+            code: 0xff,
+            value: 0,
+        };
+        assert!(process_event(&unknown, &axis_ranges, &shoulder_ranges).is_none());
+    }
+
+    #[test]
+    fn diff_state_skips_keys_never_previously_recorded() {
+        let mut current = State::new();
+        let mut fresh = State::new();
+        fresh.axes.insert(Axis::LX, 0);
+
+        // First ever resync: nothing was recorded before, so even though
+        // `fresh` has a value, there's no prior value to have changed
+        // from - no event, just seeding.
+        let events = diff_state(&fresh, &mut current);
+        assert!(events.is_empty());
+        assert_eq!(current.axes.get(&Axis::LX), Some(&0));
+
+        // Now that `current` has a recorded value, an actual change is
+        // reported...
+        fresh.axes.insert(Axis::LX, 42);
+        let events = diff_state(&fresh, &mut current);
+        assert_eq!(events.len(), 1);
+        match events[0] {
+            Event::Axis(Axis::LX, 42) => {}
+            other => panic!("unexpected: {:?}", other),
+        }
+
+        // ...but re-diffing the same value again reports nothing.
+        let events = diff_state(&fresh, &mut current);
+        assert!(events.is_empty());
+    }
+}
+
 // ****************************************************************************
 //
 // End Of File
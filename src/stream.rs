@@ -0,0 +1,105 @@
+//! # stream
+//!
+//! An async alternative to the background-thread/channel API in
+//! `lib.rs`, for callers who are already running a `tokio` reactor and
+//! would rather `.await` the next `Event` than block a thread on it.
+//!
+//! Only compiled in with `--features tokio`.
+
+// ****************************************************************************
+//
+// Imports
+//
+// ****************************************************************************
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::unix::AsyncFd;
+
+use evdev;
+use {process_event, read_axis_ranges, read_shoulder_ranges};
+use {Axis, AxisRange, Error, Event, Result, Shoulder};
+
+// ****************************************************************************
+//
+// Public Types
+//
+// ****************************************************************************
+
+/// Streams decoded `Event`s from a SIXAXIS/DUALSHOCK3 evdev node without
+/// spawning a background thread, for use inside a `tokio` runtime.
+pub struct EventStream {
+    fd: AsyncFd<fs::File>,
+    axis_ranges: HashMap<Axis, AxisRange>,
+    shoulder_ranges: HashMap<Shoulder, AxisRange>,
+}
+
+// ****************************************************************************
+//
+// Public Functions
+//
+// ****************************************************************************
+
+impl EventStream {
+    /// Open `path` and start streaming its events. The device is put in
+    /// non-blocking mode so it can be driven by `tokio`'s reactor.
+    pub fn new<P: AsRef<path::Path>>(path: P) -> Result<EventStream> {
+        let f = fs::File::open(path)?;
+        let axis_ranges = read_axis_ranges(&f)?;
+        let shoulder_ranges = read_shoulder_ranges(&f)?;
+        evdev::set_nonblocking(&f)?;
+        let fd = AsyncFd::new(f).map_err(|_| Error::IOError)?;
+        Ok(EventStream {
+            fd,
+            axis_ranges,
+            shoulder_ranges,
+        })
+    }
+}
+
+impl futures_core::Stream for EventStream {
+    type Item = Result<Event>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = match this.fd.poll_read_ready(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(_)) => return Poll::Ready(Some(Err(Error::IOError))),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let mut buf = [0u8; evdev::INPUT_EVENT_SIZE];
+            match guard.try_io(|inner| {
+                // `try_io` only hands us `&AsyncFd<File>`, but `&File`
+                // implements `Read` too, so we don't need `get_mut`.
+                let mut f = inner.get_ref();
+                io::Read::read_exact(&mut f, &mut buf)
+            }) {
+                Ok(Ok(())) => {
+                    let raw = evdev::parse_event(&buf);
+                    match process_event(&raw, &this.axis_ranges, &this.shoulder_ranges) {
+                        Some(ev) => return Poll::Ready(Some(Ok(ev))),
+                        // EV_SYN and anything we don't decode: keep polling.
+                        None => continue,
+                    }
+                }
+                Ok(Err(_)) => return Poll::Ready(Some(Err(Error::IOError))),
+                // Would block: the readiness guard has cleared itself,
+                // loop back round to wait for the next wakeup.
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// ****************************************************************************
+//
+// End Of File
+//
+// ****************************************************************************